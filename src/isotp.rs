@@ -0,0 +1,319 @@
+//! ISO-TP (ISO 15765-2) transport layer on top of the raw CAN frame API.
+//!
+//! This lets payloads larger than a single 8-byte CAN frame (up to 4095 bytes) be
+//! segmented on send and reassembled on receive, which is what's needed to talk to
+//! automotive ECUs (UDS, diagnostics, etc.) over this module.
+
+use arrayvec::ArrayVec;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::I2c;
+
+use crate::{CanFrame, Error, Identifier, LonganLabsI2CCan};
+
+/// The largest payload an ISO-TP message can carry: a 12-bit length field tops out at 4095.
+pub const MAX_LEN: usize = 4095;
+
+/// Flow status carried in a Flow Control frame's PCI byte.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum FlowStatus {
+    /// The sender may continue sending Consecutive Frames.
+    ContinueToSend = 0,
+    /// The sender must wait for another Flow Control frame before continuing.
+    Wait = 1,
+    /// The receiver can't keep up; the sender must abort.
+    Overflow = 2,
+}
+
+/// An ISO-TP wrapper around [LonganLabsI2CCan] that segments and reassembles multi-frame
+/// payloads.
+pub struct IsoTp<I: I2c, D: DelayNs> {
+    can: LonganLabsI2CCan<I>,
+    delay: D,
+    block_size: u8,
+    separation_time_ms: u8,
+}
+
+impl<I: I2c, D: DelayNs> IsoTp<I, D> {
+    /// Wraps a CAN interface. By default the Flow Control frames we send as a receiver
+    /// advertise a block size and separation time of `0`, letting the peer send the whole
+    /// message in one block with no delay between Consecutive Frames.
+    pub fn new(can: LonganLabsI2CCan<I>, delay: D) -> Self {
+        Self {
+            can,
+            delay,
+            block_size: 0,
+            separation_time_ms: 0,
+        }
+    }
+
+    /// Sets the block size we advertise in the Flow Control frames we send as a receiver,
+    /// i.e. how many Consecutive Frames the peer may send before waiting for another Flow
+    /// Control frame from us. `0` means "send the whole message in one block". Has no effect
+    /// on how we pace our own sends — that's governed by the peer's Flow Control frames.
+    pub fn set_block_size(&mut self, block_size: u8) {
+        self.block_size = block_size;
+    }
+
+    /// Sets the minimum separation time, in milliseconds, that we advertise in the Flow
+    /// Control frames we send as a receiver, asking the peer to wait at least this long
+    /// between the Consecutive Frames it sends us.
+    pub fn set_separation_time_ms(&mut self, separation_time_ms: u8) {
+        self.separation_time_ms = separation_time_ms;
+    }
+
+    /// Sends `data` as one or more CAN frames under `id`, segmenting it per ISO-TP if it
+    /// doesn't fit in a Single Frame.
+    pub async fn send(&mut self, id: Identifier, data: &[u8]) -> Result<(), IsoTpError<I::Error>> {
+        if data.len() > MAX_LEN {
+            return Err(IsoTpError::FrameTooLarge);
+        }
+
+        if data.len() <= 7 {
+            return self.send_single_frame(id, data).await;
+        }
+
+        let mut sent = self.send_first_frame(id, data).await?;
+        let (mut peer_block_size, mut peer_separation_time_ms) = self.await_flow_control().await?;
+
+        let mut sequence = 1u8;
+        let mut frames_in_block = 0u8;
+
+        while sent < data.len() {
+            let chunk = &data[sent..(sent + 7).min(data.len())];
+            self.send_consecutive_frame(id, sequence, chunk).await?;
+            sent += chunk.len();
+            sequence = (sequence + 1) % 16;
+            frames_in_block += 1;
+
+            if sent == data.len() {
+                break;
+            }
+
+            if peer_block_size != 0 && frames_in_block >= peer_block_size {
+                (peer_block_size, peer_separation_time_ms) = self.await_flow_control().await?;
+                frames_in_block = 0;
+            } else if peer_separation_time_ms != 0 {
+                self.delay.delay_ms(peer_separation_time_ms as u32).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the next complete ISO-TP message, reassembling it from Consecutive Frames
+    /// if it was segmented, and driving the Flow Control handshake on our end.
+    pub async fn recv(&mut self) -> Result<ArrayVec<u8, MAX_LEN>, IsoTpError<I::Error>> {
+        let frame = self.can.receive_frame().await?;
+        let pci_byte = *frame.data.first().ok_or(IsoTpError::UnexpectedFrame)?;
+
+        match pci_byte >> 4 {
+            0x0 => {
+                let len = (pci_byte & 0x0F) as usize;
+                if len > frame.data.len() - 1 {
+                    return Err(IsoTpError::UnexpectedFrame);
+                }
+
+                let mut buffer = ArrayVec::new();
+                buffer
+                    .try_extend_from_slice(&frame.data[1..][..len])
+                    .map_err(|_| IsoTpError::FrameTooLarge)?;
+                Ok(buffer)
+            }
+            0x1 => {
+                if frame.data.len() < 2 {
+                    return Err(IsoTpError::UnexpectedFrame);
+                }
+
+                let len = (((pci_byte & 0x0F) as usize) << 8) | frame.data[1] as usize;
+                if len > MAX_LEN {
+                    return Err(IsoTpError::FrameTooLarge);
+                }
+
+                let mut buffer = ArrayVec::new();
+                buffer
+                    .try_extend_from_slice(&frame.data[2..])
+                    .map_err(|_| IsoTpError::FrameTooLarge)?;
+
+                self.send_flow_control(frame.identifier, FlowStatus::ContinueToSend)
+                    .await?;
+
+                let mut sequence = 1u8;
+                let mut frames_in_block = 0u8;
+
+                while buffer.len() < len {
+                    let cf = self.can.receive_frame().await?;
+                    let cf_pci = *cf.data.first().ok_or(IsoTpError::UnexpectedFrame)?;
+
+                    if cf_pci >> 4 != 0x2 {
+                        return Err(IsoTpError::UnexpectedFrame);
+                    }
+
+                    if cf_pci & 0x0F != sequence {
+                        return Err(IsoTpError::SequenceGap);
+                    }
+
+                    if cf.data.len() < 2 {
+                        return Err(IsoTpError::UnexpectedFrame);
+                    }
+
+                    let take = (len - buffer.len()).min(cf.data.len() - 1);
+                    buffer
+                        .try_extend_from_slice(&cf.data[1..][..take])
+                        .map_err(|_| IsoTpError::FrameTooLarge)?;
+
+                    sequence = (sequence + 1) % 16;
+                    frames_in_block += 1;
+
+                    if self.block_size != 0
+                        && frames_in_block >= self.block_size
+                        && buffer.len() < len
+                    {
+                        self.send_flow_control(frame.identifier, FlowStatus::ContinueToSend)
+                            .await?;
+                        frames_in_block = 0;
+                    }
+                }
+
+                Ok(buffer)
+            }
+            _ => Err(IsoTpError::UnexpectedFrame),
+        }
+    }
+
+    async fn send_single_frame(
+        &mut self,
+        id: Identifier,
+        data: &[u8],
+    ) -> Result<(), IsoTpError<I::Error>> {
+        let mut frame_data = ArrayVec::<u8, 8>::new();
+        frame_data.push(data.len() as u8);
+        frame_data.try_extend_from_slice(data).unwrap();
+
+        self.can
+            .send_frame(CanFrame {
+                identifier: id,
+                remote_transmission_request: false,
+                data: frame_data,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Sends the First Frame and returns how many payload bytes it carried.
+    async fn send_first_frame(
+        &mut self,
+        id: Identifier,
+        data: &[u8],
+    ) -> Result<usize, IsoTpError<I::Error>> {
+        let len = data.len() as u16;
+        let mut frame_data = ArrayVec::<u8, 8>::new();
+        frame_data.push(0x10 | ((len >> 8) as u8 & 0x0F));
+        frame_data.push((len & 0xFF) as u8);
+        frame_data.try_extend_from_slice(&data[..6]).unwrap();
+
+        self.can
+            .send_frame(CanFrame {
+                identifier: id,
+                remote_transmission_request: false,
+                data: frame_data,
+            })
+            .await?;
+
+        Ok(6)
+    }
+
+    async fn send_consecutive_frame(
+        &mut self,
+        id: Identifier,
+        sequence: u8,
+        chunk: &[u8],
+    ) -> Result<(), IsoTpError<I::Error>> {
+        let mut frame_data = ArrayVec::<u8, 8>::new();
+        frame_data.push(0x20 | sequence);
+        frame_data.try_extend_from_slice(chunk).unwrap();
+
+        self.can
+            .send_frame(CanFrame {
+                identifier: id,
+                remote_transmission_request: false,
+                data: frame_data,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn send_flow_control(
+        &mut self,
+        id: Identifier,
+        status: FlowStatus,
+    ) -> Result<(), IsoTpError<I::Error>> {
+        let mut frame_data = ArrayVec::<u8, 8>::new();
+        frame_data.push(0x30 | status as u8);
+        frame_data.push(self.block_size);
+        frame_data.push(self.separation_time_ms);
+
+        self.can
+            .send_frame(CanFrame {
+                identifier: id,
+                remote_transmission_request: false,
+                data: frame_data,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Waits for a Flow Control frame, retrying on `Wait` and returning the peer's advertised
+    /// `(block_size, separation_time_ms)` once it sends `ContinueToSend`.
+    ///
+    /// Per ISO 15765-2 it's the peer's BS/STmin, not our own [block_size](Self::set_block_size)/
+    /// [separation_time_ms](Self::set_separation_time_ms), that must pace what we send next;
+    /// those setters only control what we advertise in the FC frames we send as a receiver.
+    async fn await_flow_control(&mut self) -> Result<(u8, u8), IsoTpError<I::Error>> {
+        loop {
+            let frame = self.can.receive_frame().await?;
+            let Some(&pci_byte) = frame.data.first() else {
+                continue;
+            };
+
+            if pci_byte >> 4 != 0x3 {
+                continue;
+            }
+
+            match pci_byte & 0x0F {
+                0 => {
+                    let block_size = frame.data.get(1).copied().unwrap_or(0);
+                    let separation_time_ms = frame.data.get(2).copied().unwrap_or(0);
+                    return Ok((block_size, separation_time_ms));
+                }
+                1 => continue,
+                2 => return Err(IsoTpError::FlowControlOverflow),
+                _ => return Err(IsoTpError::UnexpectedFrame),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum IsoTpError<IE: embedded_hal_async::i2c::Error> {
+    Can(Error<IE>),
+    /// The payload doesn't fit in ISO-TP's 12-bit length field.
+    FrameTooLarge,
+    /// A Consecutive Frame arrived with an unexpected sequence number.
+    SequenceGap,
+    /// A received frame didn't fit the transfer: it was empty or malformed, it wasn't a Single
+    /// or First Frame where a new message was expected, it wasn't a Consecutive Frame during
+    /// reassembly, or it was a Flow Control frame with an unknown flow status.
+    UnexpectedFrame,
+    /// The peer reported it can't keep up with the segmented transfer.
+    FlowControlOverflow,
+}
+
+impl<IE: embedded_hal_async::i2c::Error> From<Error<IE>> for IsoTpError<IE> {
+    fn from(e: Error<IE>) -> Self {
+        Self::Can(e)
+    }
+}
@@ -1,7 +1,13 @@
 #![no_std]
 
+pub mod isotp;
+
 use arrayvec::ArrayVec;
+use async_stream::try_stream;
+use embedded_can::{ExtendedId, Id, StandardId};
+use embedded_hal_async::delay::DelayNs;
 use embedded_hal_async::i2c::I2c;
+use futures_core::Stream;
 
 pub struct LonganLabsI2CCan<I: I2c> {
     interface: I,
@@ -54,8 +60,8 @@ impl<I: I2c> LonganLabsI2CCan<I> {
         let mut buffer = [0; 17];
 
         buffer[0] = 0x30; // Register address
-        buffer[1..5].copy_from_slice(&frame.id.to_be_bytes());
-        buffer[5] = frame.extended_id as u8;
+        buffer[1..5].copy_from_slice(&frame.identifier.raw().to_be_bytes());
+        buffer[5] = frame.identifier.is_extended() as u8;
         buffer[6] = frame.remote_transmission_request as u8;
         buffer[7] = frame.data.len() as u8;
         buffer[8..][..frame.data.len()].copy_from_slice(&frame.data);
@@ -72,6 +78,13 @@ impl<I: I2c> LonganLabsI2CCan<I> {
             return Ok(None);
         }
 
+        self.read_frame().await.map(Some)
+    }
+
+    /// Reads and parses one frame from the `0x40` register, without checking
+    /// `available_frames` first. Only safe to call when the caller already knows a frame is
+    /// waiting.
+    async fn read_frame(&mut self) -> Result<CanFrame, Error<I::Error>> {
         let mut buffer = [0; 16];
 
         self.interface.write(self.address, &[0x40]).await?;
@@ -84,7 +97,7 @@ impl<I: I2c> LonganLabsI2CCan<I> {
             return Err(Error::InvalidChecksum);
         }
 
-        let id = u32::from_be_bytes(buffer[0..4].try_into().unwrap());
+        let raw_id = u32::from_be_bytes(buffer[0..4].try_into().unwrap());
         let extended_id = buffer[4] > 0;
         let remote_transmission_request = buffer[5] > 0;
         let data_len = buffer[6] as usize;
@@ -93,14 +106,19 @@ impl<I: I2c> LonganLabsI2CCan<I> {
             return Err(Error::DataTooLarge);
         }
 
+        let identifier = if extended_id {
+            Identifier::new_extended(raw_id)?
+        } else {
+            Identifier::new_standard(raw_id as u16)?
+        };
+
         let data = ArrayVec::try_from(&buffer[7..][..data_len]).unwrap();
 
-        Ok(Some(CanFrame {
-            id,
-            extended_id,
+        Ok(CanFrame {
+            identifier,
             remote_transmission_request,
             data,
-        }))
+        })
     }
 
     /// Keep polling until a CAN frame is received
@@ -112,7 +130,148 @@ impl<I: I2c> LonganLabsI2CCan<I> {
         }
     }
 
-    // TODO: Create functions for the filters and masks
+    /// Drains up to `N` currently available frames into `buf` in one go: a single
+    /// `available_frames` round-trip followed by that many back-to-back `0x40` reads, instead
+    /// of one `available_frames`/`try_receive_frame` pair per call, so a caller doesn't fall
+    /// behind the 16-frame hardware FIFO on bursty traffic and lose frames to overwrite.
+    ///
+    /// Stops early, without returning an error, on the first [Error::InvalidChecksum],
+    /// [Error::DataTooLarge] or [Error::InvalidId] so the frames already pushed to `buf` are
+    /// still returned. The number of frames actually read is returned; this can be less than
+    /// `available_frames` if `buf` fills up first or a corrupt frame was encountered.
+    pub async fn receive_all<const N: usize>(
+        &mut self,
+        buf: &mut ArrayVec<CanFrame, N>,
+    ) -> Result<usize, Error<I::Error>> {
+        let available = self.available_frames().await?;
+        let mut received = 0;
+
+        for _ in 0..available {
+            if buf.is_full() {
+                break;
+            }
+
+            let frame = match self.read_frame().await {
+                Ok(frame) => frame,
+                Err(Error::InvalidChecksum) | Err(Error::DataTooLarge) | Err(Error::InvalidId) => {
+                    break
+                }
+                Err(err) => return Err(err),
+            };
+
+            buf.push(frame);
+            received += 1;
+        }
+
+        Ok(received)
+    }
+
+    /// Returns a [Stream] of incoming CAN frames instead of busy-polling.
+    ///
+    /// Each time the FIFO is empty, the stream backs off for `poll_backoff_ms` using `delay`
+    /// instead of hammering the bus with back-to-back `available_frames`/`try_receive_frame`
+    /// round-trips. Use [frames_with_interrupt](Self::frames_with_interrupt) instead if the
+    /// module's INT line is wired up and a fixed backoff isn't needed.
+    pub fn frames<'a, D>(
+        &'a mut self,
+        mut delay: D,
+        poll_backoff_ms: u32,
+    ) -> impl Stream<Item = Result<CanFrame, Error<I::Error>>> + 'a
+    where
+        D: DelayNs + 'a,
+    {
+        try_stream! {
+            loop {
+                if let Some(frame) = self.try_receive_frame().await? {
+                    yield frame;
+                    continue;
+                }
+
+                delay.delay_ms(poll_backoff_ms).await;
+            }
+        }
+    }
+
+    /// Like [frames](Self::frames), but waits on `wait_for_interrupt` (e.g. a future tied to
+    /// the module's INT line) between reads instead of a fixed backoff delay.
+    pub fn frames_with_interrupt<'a, W, F>(
+        &'a mut self,
+        mut wait_for_interrupt: W,
+    ) -> impl Stream<Item = Result<CanFrame, Error<I::Error>>> + 'a
+    where
+        W: FnMut() -> F + 'a,
+        F: core::future::Future<Output = ()> + 'a,
+    {
+        try_stream! {
+            loop {
+                if let Some(frame) = self.try_receive_frame().await? {
+                    yield frame;
+                    continue;
+                }
+
+                wait_for_interrupt().await;
+            }
+        }
+    }
+
+    /// Send any frame implementing [embedded_can::Frame] on the bus.
+    ///
+    /// This lets this module be used with code written against the generic `embedded-can`
+    /// abstraction instead of this crate's own [CanFrame].
+    pub async fn send<F: embedded_can::Frame>(&mut self, frame: &F) -> Result<(), Error<I::Error>> {
+        let frame = CanFrame::from_embedded_can(frame).ok_or(Error::DataTooLarge)?;
+        self.send_frame(frame).await
+    }
+
+    /// Keep polling until a frame implementing [embedded_can::Frame] is received.
+    pub async fn receive<F: embedded_can::Frame>(&mut self) -> Result<F, Error<I::Error>> {
+        let frame = self.receive_frame().await?;
+
+        if frame.remote_transmission_request {
+            F::new_remote(frame.id(), frame.data.len()).ok_or(Error::DataTooLarge)
+        } else {
+            F::new(frame.id(), &frame.data).ok_or(Error::DataTooLarge)
+        }
+    }
+
+    /// Configure one of the module's acceptance masks.
+    ///
+    /// Bits set in the mask select which bits of an incoming identifier must match the
+    /// corresponding [FilterBank]'s filter for the frame to be accepted into the FIFO.
+    pub async fn set_mask(&mut self, bank: MaskBank, mask: Identifier) -> Result<(), Error<I::Error>> {
+        let mut buffer = [0; 7];
+
+        buffer[0] = bank as u8; // Register address
+        buffer[1..5].copy_from_slice(&mask.raw().to_be_bytes());
+        buffer[5] = mask.is_extended() as u8;
+        buffer[6] = Self::make_checksum(&buffer[1..6]);
+
+        self.interface.write(self.address, &buffer).await?;
+
+        Ok(())
+    }
+
+    /// Configure one of the module's acceptance filters.
+    ///
+    /// Only frames whose identifier matches `filter` under the associated [MaskBank]'s mask
+    /// are placed in the receive FIFO; everything else is dropped by the hardware before it
+    /// can overwrite unread frames.
+    pub async fn set_filter(
+        &mut self,
+        bank: FilterBank,
+        filter: Identifier,
+    ) -> Result<(), Error<I::Error>> {
+        let mut buffer = [0; 7];
+
+        buffer[0] = bank as u8; // Register address
+        buffer[1..5].copy_from_slice(&filter.raw().to_be_bytes());
+        buffer[5] = filter.is_extended() as u8;
+        buffer[6] = Self::make_checksum(&buffer[1..6]);
+
+        self.interface.write(self.address, &buffer).await?;
+
+        Ok(())
+    }
 
     fn make_checksum(data: &[u8]) -> u8 {
         let mut sum: u32 = data.iter().map(|byte| *byte as u32).sum();
@@ -128,12 +287,141 @@ impl<I: I2c> LonganLabsI2CCan<I> {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CanFrame {
-    pub id: u32,
-    pub extended_id: bool,
+    pub identifier: Identifier,
     pub remote_transmission_request: bool,
     pub data: ArrayVec<u8, 8>,
 }
 
+impl CanFrame {
+    /// Converts any [embedded_can::Frame] into a [CanFrame], returning `None` if the
+    /// data doesn't fit in this module's 8-byte frame.
+    fn from_embedded_can<F: embedded_can::Frame>(frame: &F) -> Option<Self> {
+        Some(Self {
+            identifier: frame.id().into(),
+            remote_transmission_request: frame.is_remote_frame(),
+            data: ArrayVec::try_from(frame.data()).ok()?,
+        })
+    }
+}
+
+impl embedded_can::Frame for CanFrame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        Some(Self {
+            identifier: id.into().into(),
+            remote_transmission_request: false,
+            data: ArrayVec::try_from(data).ok()?,
+        })
+    }
+
+    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+        if dlc > 8 {
+            return None;
+        }
+
+        Some(Self {
+            identifier: id.into().into(),
+            remote_transmission_request: true,
+            data: core::iter::repeat(0).take(dlc).collect(),
+        })
+    }
+
+    fn is_extended(&self) -> bool {
+        self.identifier.is_extended()
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.remote_transmission_request
+    }
+
+    fn id(&self) -> Id {
+        self.identifier.into()
+    }
+
+    fn dlc(&self) -> usize {
+        self.data.len()
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// A validated CAN identifier, either an 11-bit standard ID or a 29-bit extended ID.
+///
+/// Unlike a bare `u32`, constructing one of these can't produce a standard ID that's out
+/// of range or silently mix up the standard/extended flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Identifier {
+    Standard(StandardId),
+    Extended(ExtendedId),
+}
+
+impl Identifier {
+    /// Creates a standard (11-bit) identifier, rejecting values above `0x7FF`.
+    pub fn new_standard(id: u16) -> Result<Self, InvalidIdError> {
+        StandardId::new(id).map(Self::Standard).ok_or(InvalidIdError)
+    }
+
+    /// Creates an extended (29-bit) identifier, rejecting values above `0x1FFF_FFFF`.
+    pub fn new_extended(id: u32) -> Result<Self, InvalidIdError> {
+        ExtendedId::new(id).map(Self::Extended).ok_or(InvalidIdError)
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(self, Self::Extended(_))
+    }
+
+    /// The identifier value as it's transmitted on the wire to the module.
+    fn raw(&self) -> u32 {
+        match self {
+            Self::Standard(id) => id.as_raw() as u32,
+            Self::Extended(id) => id.as_raw(),
+        }
+    }
+}
+
+impl From<Identifier> for Id {
+    fn from(id: Identifier) -> Self {
+        match id {
+            Identifier::Standard(id) => Id::Standard(id),
+            Identifier::Extended(id) => Id::Extended(id),
+        }
+    }
+}
+
+impl From<Id> for Identifier {
+    fn from(id: Id) -> Self {
+        match id {
+            Id::Standard(id) => Identifier::Standard(id),
+            Id::Extended(id) => Identifier::Extended(id),
+        }
+    }
+}
+
+/// An identifier value didn't fit in the bit width of the requested [Identifier] variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidIdError;
+
+/// One of the module's two acceptance mask banks, each paired with up to three [FilterBank]s.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum MaskBank {
+    Mask0 = 0x60,
+    Mask1 = 0x65,
+}
+
+/// One of the module's six acceptance filter banks.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum FilterBank {
+    Filter0 = 0x70,
+    Filter1 = 0x80,
+    Filter2 = 0x90,
+    Filter3 = 0xA0,
+    Filter4 = 0xB0,
+    Filter5 = 0xC0,
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy)]
 pub enum BaudRate {
@@ -179,7 +467,8 @@ pub enum BaudRate {
 pub enum Error<IE: embedded_hal_async::i2c::Error> {
     InterfaceError(IE),
     InvalidChecksum,
-    DataTooLarge
+    DataTooLarge,
+    InvalidId,
 }
 
 impl<IE: embedded_hal_async::i2c::Error> From<IE> for Error<IE> {
@@ -187,3 +476,9 @@ impl<IE: embedded_hal_async::i2c::Error> From<IE> for Error<IE> {
         Self::InterfaceError(e)
     }
 }
+
+impl<IE: embedded_hal_async::i2c::Error> From<InvalidIdError> for Error<IE> {
+    fn from(_: InvalidIdError) -> Self {
+        Self::InvalidId
+    }
+}